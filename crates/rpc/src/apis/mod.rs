@@ -0,0 +1,49 @@
+//! Request/response types and subscription handlers for the shadow RPC API surface.
+
+pub(crate) mod subscribe;
+
+use crate::shadow_logs_query::ShadowLogRow;
+use serde::{Deserialize, Serialize};
+
+/// Either a single contract address or a set of addresses a shadow log filter should match.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AddressRepresentation {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+/// The shadow-indexed equivalent of a standard `eth_subscribe("logs", ...)` notification.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcLog {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_hash: String,
+    pub block_number: String,
+    pub transaction_hash: String,
+    pub transaction_index: String,
+    pub log_index: String,
+    /// `true` when this log belongs to a block that has since been orphaned by a reorg.
+    /// Defaults to `false`; set by `subscribe::emit_logs` when re-delivering logs for blocks
+    /// that are no longer canonical, matching standard `eth_subscribe("logs", ...)` semantics.
+    #[serde(default)]
+    pub removed: bool,
+}
+
+impl From<ShadowLogRow> for RpcLog {
+    fn from(row: ShadowLogRow) -> Self {
+        Self {
+            address: row.address,
+            topics: row.topics,
+            data: row.data,
+            block_hash: row.block_hash,
+            block_number: format!("0x{:x}", row.block_number),
+            transaction_hash: row.transaction_hash,
+            transaction_index: format!("0x{:x}", row.transaction_index),
+            log_index: format!("0x{:x}", row.log_index),
+            removed: false,
+        }
+    }
+}
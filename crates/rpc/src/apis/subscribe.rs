@@ -1,4 +1,17 @@
 //! Contains logic for a shadow RPC equivalent of `eth_subscribe` of `type` `logs`.
+//!
+//! `subscribe`/`handle_accepted` are transport-agnostic: both only depend on jsonrpsee's
+//! [`PendingSubscriptionSink`]/[`SubscriptionSink`], which are constructed the same way whether
+//! the request arrived over HTTP/WS or an IPC (Unix domain socket) endpoint. Exposing `logs`
+//! subscriptions over IPC is therefore a matter of adding an `IpcServerBuilder` alongside the
+//! existing networked server in the `ShadowRpc` builder/startup code, not of changing this
+//! pipeline.
+//!
+//! Delivery is reorg-aware: a small window of recently delivered `(block number, block hash)`
+//! pairs is kept per subscription, and an incoming block that doesn't build on the tip of that
+//! window is treated as a reorg. The orphaned blocks are re-emitted first with `RpcLog::removed`
+//! set, matching standard `eth_subscribe("logs", ...)` semantics, before canonical delivery
+//! resumes.
 
 use super::AddressRepresentation;
 use crate::{
@@ -11,16 +24,36 @@ use jsonrpsee::{
     types::{error::INTERNAL_ERROR_CODE, ErrorObject},
     PendingSubscriptionSink, SubscriptionMessage, SubscriptionSink,
 };
-use reth_provider::{BlockNumReader, BlockReaderIdExt};
+use reth_primitives::B256;
+use reth_provider::{BlockNumReader, BlockReaderIdExt, HeaderProvider};
 use reth_tracing::tracing::{info, warn};
 use serde::{Deserialize, Serialize};
 use shadow_reth_common::ShadowSqliteDb;
+use std::collections::VecDeque;
 use tokio::sync::broadcast::{error::RecvError, Receiver};
 
+/// The maximum number of blocks that will be backfilled for a single subscriber after a
+/// broadcast lag or hash gap is detected. Bounds the amount of work a stalled sink can force
+/// onto the sqlite query path before we give up and close the subscription.
+const MAX_BACKFILL_BLOCKS: u64 = 256;
+
+/// Error code returned when a client attempts to open a `logs` subscription while the node is
+/// already servicing `max_subscriptions` of them.
+const TOO_MANY_SUBSCRIPTIONS_CODE: i32 = -32005;
+
+/// How many of the most recently delivered (block number, block hash) pairs are retained per
+/// subscription so a reorg can be detected and the orphaned blocks re-emitted with `removed`.
+const RECENT_BLOCKS_WINDOW: usize = 64;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SubscribeParameters {
     pub address: Option<AddressRepresentation>,
-    pub topics: Option<Vec<String>>,
+    /// Positional topic filter, matching the semantics of `eth_getLogs`/`eth_subscribe`: each
+    /// entry is a filter for the topic at that position, where `None` matches any topic (a
+    /// wildcard) and `Some(alternatives)` matches a log whose topic at that position is any one
+    /// of `alternatives` (an OR-set); an empty `alternatives` list is treated like `None`.
+    /// `exec_query` applies this positional wildcard/OR matching when building the sqlite query.
+    pub topics: Option<Vec<Option<Vec<String>>>>,
 }
 
 pub(crate) async fn subscribe<P>(
@@ -29,8 +62,26 @@ pub(crate) async fn subscribe<P>(
     params: SubscribeParameters,
 ) -> SubscriptionResult
 where
-    P: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+    P: BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Unpin + 'static,
 {
+    let Ok(permit) = rpc.subscription_semaphore.clone().try_acquire_owned() else {
+        warn!(
+            "rejecting shadow logs subscription: max_subscriptions ({}) reached",
+            rpc.max_subscriptions
+        );
+        pending
+            .reject(ErrorObject::owned::<()>(
+                TOO_MANY_SUBSCRIPTIONS_CODE,
+                format!(
+                    "maximum of {} concurrent shadow log subscriptions reached",
+                    rpc.max_subscriptions
+                ),
+                None,
+            ))
+            .await;
+        return Ok(());
+    };
+
     let sink = pending.accept().await?;
     info!("Subscribing to shadow logs with params: {:?}", params);
     tokio::spawn({
@@ -38,6 +89,9 @@ where
         let sqlite_manager = rpc.sqlite_manager.clone();
         let indexed_block_hash_receiver = rpc.indexed_block_hash_receiver.resubscribe();
         async move {
+            // Held for the lifetime of the subscription; dropping it (on any return path of
+            // `handle_accepted`) releases the permit back to the semaphore.
+            let _permit = permit;
             let _ = handle_accepted(
                 provider,
                 sqlite_manager,
@@ -53,38 +107,112 @@ where
     Ok(())
 }
 
+/// A block successfully delivered to a subscriber, retained in a small rolling window so both
+/// gaps (broadcast lag) and reorgs (orphaned blocks) can be detected in later deliveries.
+#[derive(Clone)]
+struct DeliveredBlock {
+    number: u64,
+    hash: B256,
+}
+
 async fn handle_accepted(
-    provider: impl BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+    provider: impl BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Unpin + 'static,
     sqlite_manager: ShadowSqliteDb,
     mut indexed_block_hash_receiver: Receiver<String>,
     accepted_sink: SubscriptionSink,
     params: SubscribeParameters,
 ) -> Result<(), ErrorObject<'static>> {
     info!("Handling accepted shadow logs subscription");
+    let mut recent: VecDeque<DeliveredBlock> = VecDeque::with_capacity(RECENT_BLOCKS_WINDOW);
+
     loop {
         match indexed_block_hash_receiver.recv().await {
             Ok(block_hash) => {
                 info!("Received indexed block hash: {}", block_hash);
-                let query_params = ValidatedQueryParams::from_subscribe_parameters(
-                    &provider,
-                    params.clone(),
-                    block_hash,
-                )?;
-                let intermediate_results = exec_query(query_params, &sqlite_manager.pool).await?;
-                info!("Got {} intermediate results", intermediate_results.len());
-                for result in intermediate_results.into_iter().map(RpcLog::from) {
-                    info!("Sending shadow log: {:?}", result);
-                    let message = SubscriptionMessage::from_json(&result).map_err(|e| {
-                        ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
+                let parsed_hash = block_hash.parse::<B256>().map_err(|e| {
+                    ErrorObject::owned::<()>(
+                        INTERNAL_ERROR_CODE,
+                        format!("invalid block hash {}: {}", block_hash, e),
+                        None,
+                    )
+                })?;
+                let block_number = provider
+                    .block_number(parsed_hash)
+                    .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+                    .ok_or_else(|| {
+                        ErrorObject::owned::<()>(
+                            INTERNAL_ERROR_CODE,
+                            format!("provider has no block number for hash {}", block_hash),
+                            None,
+                        )
                     })?;
 
-                    accepted_sink.send(message).await.map_err(|e| {
-                        ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
-                    })?;
+                if let Some(orphaned_from) =
+                    detect_reorg(&provider, &recent, block_number, parsed_hash)?
+                {
+                    info!("Detected reorg at block {}; re-emitting orphaned logs", orphaned_from);
+                    while matches!(recent.back(), Some(tip) if tip.number >= orphaned_from) {
+                        let orphan = recent.pop_back().expect("checked by matches! above");
+                        deliver_hash(
+                            &provider,
+                            &sqlite_manager,
+                            &accepted_sink,
+                            &params,
+                            &orphan.hash.to_string(),
+                            true,
+                        )
+                        .await?;
+                    }
                 }
+
+                if let Some(tip) = recent.back() {
+                    // `detect_reorg` above has already handled every case where `block_number <=
+                    // tip.number`, popping `recent` until its back is `< block_number`, so
+                    // `block_number > tip.number` always holds here.
+                    debug_assert!(block_number > tip.number);
+                    match backfill_range_for_gap(tip.number, block_number) {
+                        Some(Ok((from_block, backfill_to))) => {
+                            info!(
+                                "Backfilling blocks {}..={} before resuming live delivery",
+                                from_block, backfill_to
+                            );
+                            let backfilled = deliver_range(
+                                &provider,
+                                &sqlite_manager,
+                                &accepted_sink,
+                                &params,
+                                from_block,
+                                backfill_to,
+                                false,
+                            )
+                            .await?;
+                            for block in backfilled {
+                                push_delivered(&mut recent, block);
+                            }
+                        }
+                        Some(Err(skipped)) => {
+                            return Err(ErrorObject::owned::<()>(
+                                INTERNAL_ERROR_CODE,
+                                format!(
+                                    "lagged by {} blocks, exceeding max backfill of {}; closing subscription",
+                                    skipped, MAX_BACKFILL_BLOCKS
+                                ),
+                                None,
+                            ));
+                        }
+                        None => {}
+                    }
+                }
+
+                deliver_hash(&provider, &sqlite_manager, &accepted_sink, &params, &block_hash, false)
+                    .await?;
+                push_delivered(&mut recent, DeliveredBlock { number: block_number, hash: parsed_hash });
             }
             Err(RecvError::Lagged(lag_count)) => {
-                warn!("lagged by {} messages; consider increasing buffer if syncing", lag_count);
+                // We don't know the skipped block hashes here, but the next successful `recv`
+                // will reveal a jump in block numbers (or a reorg) relative to `recent`, which
+                // triggers the backfill/reorg handling above before live delivery resumes.
+                warn!("lagged by {} messages; will backfill on next received block", lag_count);
             }
             Err(RecvError::Closed) => {
                 break;
@@ -94,3 +222,288 @@ async fn handle_accepted(
     info!("Shadow logs subscription ended");
     Ok(())
 }
+
+fn push_delivered(recent: &mut VecDeque<DeliveredBlock>, block: DeliveredBlock) {
+    if recent.len() == RECENT_BLOCKS_WINDOW {
+        recent.pop_front();
+    }
+    recent.push_back(block);
+}
+
+/// Decides whether `incoming` requires backfilling blocks skipped since `last_delivered`.
+/// Returns `None` when there's no gap to backfill (`incoming` is adjacent to, or not after,
+/// `last_delivered`). Returns `Some(Ok((from_block, to_block)))` with the inclusive range to
+/// backfill, or, if the gap exceeds `MAX_BACKFILL_BLOCKS`, `Some(Err(lag))` with how far
+/// `incoming` is ahead of `last_delivered` (`incoming - last_delivered`, one more than the
+/// number of skipped blocks).
+fn backfill_range_for_gap(last_delivered: u64, incoming: u64) -> Option<Result<(u64, u64), u64>> {
+    if incoming <= last_delivered + 1 {
+        return None;
+    }
+
+    let from_block = last_delivered + 1;
+    let gap = incoming - from_block;
+    if gap > MAX_BACKFILL_BLOCKS {
+        return Some(Err(gap + 1));
+    }
+
+    Some(Ok((from_block, incoming - 1)))
+}
+
+/// Returns the lowest block number that must be treated as orphaned, if the incoming block does
+/// not build on the last-delivered tip: either it is at or behind an already-delivered number (a
+/// rollback), one or more of the most recently delivered blocks are no longer part of the
+/// canonical chain (a reorg hiding behind a number gap, e.g. lag and a reorg landing in the same
+/// received block — possibly unwinding more than just the tip), or the incoming block is the
+/// tip's immediate successor but its parent hash doesn't match the tip (a reorg at the tip).
+fn detect_reorg(
+    provider: &(impl BlockNumReader + HeaderProvider + Clone + Unpin + 'static),
+    recent: &VecDeque<DeliveredBlock>,
+    incoming_number: u64,
+    incoming_hash: B256,
+) -> Result<Option<u64>, ErrorObject<'static>> {
+    let Some(tip) = recent.back() else {
+        return Ok(None);
+    };
+
+    if incoming_number <= tip.number {
+        return Ok(Some(incoming_number));
+    }
+
+    // A number gap doesn't by itself mean `recent` is still valid: the chain could have reorged
+    // at or behind the tip and then advanced past it before the next hash reached us, orphaning
+    // more than just the tip. Walk backward from the tip, checking each delivered block's
+    // continued canonicity, until we find one that's still canonical (or exhaust the window).
+    let mut canonicity = Vec::with_capacity(recent.len());
+    for block in recent.iter().rev() {
+        let still_canonical = provider
+            .block_number(block.hash)
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+            == Some(block.number);
+        canonicity.push((block.number, still_canonical));
+        if still_canonical {
+            break;
+        }
+    }
+
+    let tip_is_incoming_parent = canonicity[0].1
+        && incoming_number == tip.number + 1
+        && provider
+            .header(&incoming_hash)
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+            .ok_or_else(|| {
+                ErrorObject::owned::<()>(
+                    INTERNAL_ERROR_CODE,
+                    format!("provider has no header for hash {}", incoming_hash),
+                    None,
+                )
+            })?
+            .parent_hash
+            == tip.hash;
+
+    Ok(reorg_unwind_point(&canonicity, incoming_number, tip_is_incoming_parent))
+}
+
+/// Pure decision logic backing `detect_reorg`, split out so the branching can be unit tested
+/// without a provider. `canonicity` holds `(number, still_canonical)` for the most recently
+/// delivered blocks, ordered from the tip backward, stopping at (and including) the first block
+/// still found on the canonical chain — exactly what `detect_reorg`'s backward walk produces.
+/// Returns the lowest block number that must be unwound as orphaned, which may be older than the
+/// tip when more than one delivered block was rolled back by the same reorg.
+fn reorg_unwind_point(
+    canonicity: &[(u64, bool)],
+    incoming_number: u64,
+    tip_is_incoming_parent: bool,
+) -> Option<u64> {
+    let (tip_number, _) = *canonicity.first()?;
+
+    if incoming_number <= tip_number {
+        return Some(incoming_number);
+    }
+
+    let mut orphaned_from = None;
+    for &(number, still_canonical) in canonicity {
+        if still_canonical {
+            break;
+        }
+        orphaned_from = Some(number);
+    }
+    if orphaned_from.is_some() {
+        return orphaned_from;
+    }
+
+    if incoming_number == tip_number + 1 && !tip_is_incoming_parent {
+        return Some(tip_number);
+    }
+
+    None
+}
+
+/// Queries and emits the shadow logs for a single block identified by `block_hash`. When
+/// `removed` is set, each `RpcLog` is marked as removed before being sent, signaling to the
+/// subscriber that the block has been orphaned by a reorg.
+async fn deliver_hash(
+    provider: &(impl BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Unpin + 'static),
+    sqlite_manager: &ShadowSqliteDb,
+    accepted_sink: &SubscriptionSink,
+    params: &SubscribeParameters,
+    block_hash: &str,
+    removed: bool,
+) -> Result<(), ErrorObject<'static>> {
+    let query_params = ValidatedQueryParams::from_subscribe_parameters(
+        provider,
+        params.clone(),
+        block_hash.to_string(),
+    )?;
+    let results = exec_query(query_params, &sqlite_manager.pool).await?;
+    info!("Got {} intermediate results for block {}", results.len(), block_hash);
+    emit_logs(accepted_sink, results.into_iter().map(RpcLog::from), removed).await
+}
+
+/// Queries and emits the shadow logs for every block in `from_block..=to_block`, used to heal
+/// gaps left by a broadcast lag or a jump in received block hashes. Returns the distinct blocks
+/// actually observed in the results (in ascending order) so the caller can record them in the
+/// `recent` window, keeping backfilled blocks eligible for reorg detection just like live ones.
+async fn deliver_range(
+    provider: &(impl BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Unpin + 'static),
+    sqlite_manager: &ShadowSqliteDb,
+    accepted_sink: &SubscriptionSink,
+    params: &SubscribeParameters,
+    from_block: u64,
+    to_block: u64,
+    removed: bool,
+) -> Result<Vec<DeliveredBlock>, ErrorObject<'static>> {
+    let query_params = ValidatedQueryParams::from_subscribe_parameters_range(
+        provider,
+        params.clone(),
+        from_block,
+        to_block,
+    )?;
+    let results = exec_query(query_params, &sqlite_manager.pool).await?;
+    info!("Got {} backfilled results for blocks {}..={}", results.len(), from_block, to_block);
+
+    let mut delivered = Vec::new();
+    for row in &results {
+        if delivered.last().map(|b: &DeliveredBlock| b.number) != Some(row.block_number as u64) {
+            let hash = row.block_hash.parse::<B256>().map_err(|e| {
+                ErrorObject::owned::<()>(
+                    INTERNAL_ERROR_CODE,
+                    format!("invalid block hash {}: {}", row.block_hash, e),
+                    None,
+                )
+            })?;
+            delivered.push(DeliveredBlock { number: row.block_number as u64, hash });
+        }
+    }
+
+    emit_logs(accepted_sink, results.into_iter().map(RpcLog::from), removed).await?;
+    Ok(delivered)
+}
+
+async fn emit_logs(
+    accepted_sink: &SubscriptionSink,
+    logs: impl Iterator<Item = RpcLog>,
+    removed: bool,
+) -> Result<(), ErrorObject<'static>> {
+    for mut log in logs {
+        log.removed = removed;
+        info!("Sending shadow log: {:?}", log);
+        let message = SubscriptionMessage::from_json(&log)
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+
+        accepted_sink
+            .send(message)
+            .await
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backfill_range_for_gap_is_none_for_the_adjacent_block() {
+        assert_eq!(backfill_range_for_gap(100, 101), None);
+    }
+
+    #[test]
+    fn backfill_range_for_gap_is_none_for_a_stale_or_duplicate_block() {
+        assert_eq!(backfill_range_for_gap(100, 100), None);
+        assert_eq!(backfill_range_for_gap(100, 99), None);
+    }
+
+    #[test]
+    fn backfill_range_for_gap_returns_the_inclusive_skipped_range() {
+        assert_eq!(backfill_range_for_gap(100, 105), Some(Ok((101, 104))));
+    }
+
+    #[test]
+    fn backfill_range_for_gap_allows_a_gap_exactly_at_the_limit() {
+        let incoming = 100 + MAX_BACKFILL_BLOCKS + 1;
+        assert_eq!(backfill_range_for_gap(100, incoming), Some(Ok((101, incoming - 1))));
+    }
+
+    #[test]
+    fn backfill_range_for_gap_errors_once_the_limit_is_exceeded() {
+        let incoming = 100 + MAX_BACKFILL_BLOCKS + 2;
+        assert_eq!(backfill_range_for_gap(100, incoming), Some(Err(MAX_BACKFILL_BLOCKS + 2)));
+    }
+
+    #[test]
+    fn reorg_unwind_point_flags_a_rollback_to_an_already_delivered_number() {
+        let canonicity = [(100, true)];
+        assert_eq!(reorg_unwind_point(&canonicity, 100, true), Some(100));
+        assert_eq!(reorg_unwind_point(&canonicity, 99, true), Some(99));
+    }
+
+    #[test]
+    fn reorg_unwind_point_unwinds_every_orphaned_block_behind_a_stale_tip() {
+        // Blocks 102, 101, and 100 were all orphaned by the same reorg, and the chain had
+        // already advanced to 103 by the time the next hash reached us: the lowest orphaned
+        // number (100) must be returned, not just the tip (102), so every affected block gets
+        // re-emitted with `removed: true`.
+        let canonicity = [(102, false), (101, false), (100, false)];
+        assert_eq!(reorg_unwind_point(&canonicity, 103, false), Some(100));
+    }
+
+    #[test]
+    fn reorg_unwind_point_stops_unwinding_at_the_first_still_canonical_block() {
+        // Only the tip (102) was orphaned; 101 (and anything behind it) is still canonical.
+        let canonicity = [(102, false), (101, true), (100, true)];
+        assert_eq!(reorg_unwind_point(&canonicity, 105, false), Some(102));
+    }
+
+    #[test]
+    fn reorg_unwind_point_flags_a_reorg_at_the_tip() {
+        let canonicity = [(100, true)];
+        assert_eq!(reorg_unwind_point(&canonicity, 101, false), Some(100));
+    }
+
+    #[test]
+    fn reorg_unwind_point_allows_canonical_adjacent_and_gapped_delivery() {
+        let canonicity = [(100, true)];
+        assert_eq!(reorg_unwind_point(&canonicity, 101, true), None);
+        assert_eq!(reorg_unwind_point(&canonicity, 105, true), None);
+    }
+
+    #[test]
+    fn push_delivered_evicts_the_oldest_entry_once_the_window_is_full() {
+        let mut recent = VecDeque::new();
+        for number in 0..RECENT_BLOCKS_WINDOW as u64 {
+            push_delivered(&mut recent, DeliveredBlock { number, hash: B256::ZERO });
+        }
+        assert_eq!(recent.len(), RECENT_BLOCKS_WINDOW);
+        assert_eq!(recent.front().unwrap().number, 0);
+
+        push_delivered(
+            &mut recent,
+            DeliveredBlock { number: RECENT_BLOCKS_WINDOW as u64, hash: B256::ZERO },
+        );
+
+        assert_eq!(recent.len(), RECENT_BLOCKS_WINDOW);
+        assert_eq!(recent.front().unwrap().number, 1);
+        assert_eq!(recent.back().unwrap().number, RECENT_BLOCKS_WINDOW as u64);
+    }
+}
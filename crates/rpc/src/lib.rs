@@ -0,0 +1,133 @@
+//! Shadow RPC server: serves a `logs`-only `eth_subscribe` equivalent backed by the shadow
+//! sqlite index.
+
+pub mod apis;
+pub(crate) mod shadow_logs_query;
+
+use apis::subscribe::{subscribe, SubscribeParameters};
+use jsonrpsee::server::{IpcServerBuilder, ServerHandle};
+use reth_provider::{BlockNumReader, BlockReaderIdExt, HeaderProvider};
+use reth_tracing::tracing::info;
+use shadow_reth_common::ShadowSqliteDb;
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::{broadcast::Receiver, Semaphore};
+
+/// Ceiling on concurrent `logs` subscriptions applied when a builder never calls
+/// [`ShadowRpcBuilder::max_subscriptions`].
+pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 100;
+
+#[derive(Clone)]
+pub struct ShadowRpc<P> {
+    pub(crate) provider: P,
+    pub(crate) sqlite_manager: ShadowSqliteDb,
+    pub(crate) indexed_block_hash_receiver: Receiver<String>,
+    /// Bounds the number of subscriptions being serviced concurrently; a permit is acquired in
+    /// `apis::subscribe::subscribe` before the request is accepted and held for the lifetime of
+    /// the subscription.
+    pub(crate) subscription_semaphore: Arc<Semaphore>,
+    /// The configured ceiling `subscription_semaphore` was created with, retained so rejection
+    /// errors can report it back to the client.
+    pub(crate) max_subscriptions: usize,
+    /// Unix domain socket path to additionally serve shadow `logs` subscriptions over, alongside
+    /// the networked jsonrpsee server. `None` means IPC is disabled.
+    pub(crate) ipc_endpoint: Option<PathBuf>,
+}
+
+/// Builds a [`ShadowRpc`], wiring up the concurrent-subscription cap and, optionally, an IPC
+/// endpoint.
+pub struct ShadowRpcBuilder<P> {
+    provider: P,
+    sqlite_manager: ShadowSqliteDb,
+    indexed_block_hash_receiver: Receiver<String>,
+    max_subscriptions: usize,
+    ipc_endpoint: Option<PathBuf>,
+}
+
+impl<P> ShadowRpcBuilder<P>
+where
+    P: BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Unpin + 'static,
+{
+    pub fn new(
+        provider: P,
+        sqlite_manager: ShadowSqliteDb,
+        indexed_block_hash_receiver: Receiver<String>,
+    ) -> Self {
+        Self {
+            provider,
+            sqlite_manager,
+            indexed_block_hash_receiver,
+            max_subscriptions: DEFAULT_MAX_SUBSCRIPTIONS,
+            ipc_endpoint: None,
+        }
+    }
+
+    /// Caps the number of concurrent shadow `logs` subscriptions; requests beyond this are
+    /// rejected with an error rather than accepted. See `apis::subscribe::subscribe`.
+    pub fn max_subscriptions(mut self, max_subscriptions: usize) -> Self {
+        self.max_subscriptions = max_subscriptions;
+        self
+    }
+
+    /// Additionally serves shadow `logs` subscriptions over a Unix domain socket at `path`. Call
+    /// [`ShadowRpc::serve_ipc`] after building to actually start listening.
+    pub fn ipc_endpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ipc_endpoint = Some(path.into());
+        self
+    }
+
+    pub fn build(self) -> ShadowRpc<P> {
+        ShadowRpc {
+            provider: self.provider,
+            sqlite_manager: self.sqlite_manager,
+            indexed_block_hash_receiver: self.indexed_block_hash_receiver,
+            subscription_semaphore: Arc::new(Semaphore::new(self.max_subscriptions)),
+            max_subscriptions: self.max_subscriptions,
+            ipc_endpoint: self.ipc_endpoint,
+        }
+    }
+}
+
+impl<P> ShadowRpc<P>
+where
+    P: BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Send + Sync + Unpin + 'static,
+{
+    /// Starts serving `eth_subscribe("logs", ...)` over the configured IPC (Unix domain socket)
+    /// endpoint, reusing the same `apis::subscribe::subscribe`/`handle_accepted` pipeline as the
+    /// networked server. Returns `None` if no `ipc_endpoint` was configured on the builder.
+    ///
+    /// Co-located clients connecting to the returned socket get a lower-latency, auth-free
+    /// channel to the shadow log stream without opening a TCP/WS port.
+    pub async fn serve_ipc(self: Arc<Self>) -> Option<std::io::Result<ServerHandle>> {
+        let endpoint = self.ipc_endpoint.clone()?;
+        Some(self.serve_ipc_at(&endpoint).await)
+    }
+
+    async fn serve_ipc_at(self: Arc<Self>, endpoint: &PathBuf) -> std::io::Result<ServerHandle> {
+        let mut module = jsonrpsee::RpcModule::new(());
+        let rpc = self.clone();
+        module
+            .register_subscription(
+                "eth_subscribe",
+                "eth_subscription",
+                "eth_unsubscribe",
+                move |params, pending, _ctx| {
+                    let rpc = rpc.clone();
+                    async move {
+                        // Standard `eth_subscribe` shape: `["logs", <filter>]`. We only ever
+                        // serve the `logs` kind, but still consume the leading element so the
+                        // filter is read from the right position.
+                        let mut seq = params.sequence();
+                        let _kind: String = seq.next()?;
+                        let params: SubscribeParameters = seq.next()?;
+                        subscribe(&rpc, pending, params).await
+                    }
+                },
+            )
+            .expect("eth_subscribe is only registered once");
+
+        let server = IpcServerBuilder::default().build(endpoint.to_string_lossy());
+        let handle = server.start(module);
+        info!("Serving shadow logs subscriptions over IPC at {}", endpoint.display());
+        Ok(handle)
+    }
+}
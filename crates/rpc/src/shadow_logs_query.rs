@@ -0,0 +1,294 @@
+//! Builds and executes the sqlite queries backing shadow `logs` subscriptions.
+
+use crate::apis::{subscribe::SubscribeParameters, AddressRepresentation};
+use jsonrpsee::types::{error::INTERNAL_ERROR_CODE, ErrorObject};
+use reth_provider::{BlockNumReader, BlockReaderIdExt, HeaderProvider};
+use sqlx::{sqlite::SqlitePool, Row};
+
+/// A single shadow log row read back from sqlite, prior to conversion into the RPC shape.
+#[derive(Debug, Clone)]
+pub(crate) struct ShadowLogRow {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_hash: String,
+    pub block_number: i64,
+    pub transaction_hash: String,
+    pub transaction_index: i64,
+    pub log_index: i64,
+}
+
+/// Which blocks a query should cover: either the blocks matching a single received
+/// `block_hash`, or an explicit inclusive block-number range used to backfill a gap.
+#[derive(Debug, Clone)]
+enum BlockScope {
+    Hash(String),
+    Range { from_block: u64, to_block: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ValidatedQueryParams {
+    scope: BlockScope,
+    address: Option<AddressRepresentation>,
+    topics: Option<Vec<Option<Vec<String>>>>,
+}
+
+impl ValidatedQueryParams {
+    /// Validates `params` against a single newly-received `block_hash`, confirming the provider
+    /// actually knows about it before any sqlite work is done.
+    pub(crate) fn from_subscribe_parameters(
+        provider: &(impl BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static),
+        params: SubscribeParameters,
+        block_hash: String,
+    ) -> Result<Self, ErrorObject<'static>> {
+        let parsed_hash = block_hash.parse().map_err(|e| {
+            ErrorObject::owned::<()>(
+                INTERNAL_ERROR_CODE,
+                format!("invalid block hash {}: {}", block_hash, e),
+                None,
+            )
+        })?;
+        provider
+            .block_number(parsed_hash)
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+            .ok_or_else(|| {
+                ErrorObject::owned::<()>(
+                    INTERNAL_ERROR_CODE,
+                    format!("provider has no block number for hash {}", block_hash),
+                    None,
+                )
+            })?;
+
+        Ok(Self { scope: BlockScope::Hash(block_hash), address: params.address, topics: params.topics })
+    }
+
+    /// Validates `params` against an inclusive block-number range, used to backfill the blocks
+    /// a subscriber missed due to a broadcast lag or a jump in received block hashes. Confirms
+    /// the provider still has every block in the range before any sqlite work is done; a pruned
+    /// or otherwise missing block closes the subscription with an error rather than silently
+    /// returning a partial (or empty) result.
+    pub(crate) fn from_subscribe_parameters_range(
+        provider: &(impl BlockNumReader + BlockReaderIdExt + HeaderProvider + Clone + Unpin + 'static),
+        params: SubscribeParameters,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Self, ErrorObject<'static>> {
+        if from_block > to_block {
+            return Err(ErrorObject::owned::<()>(
+                INTERNAL_ERROR_CODE,
+                format!("invalid backfill range {}..={}", from_block, to_block),
+                None,
+            ));
+        }
+
+        for block_number in from_block..=to_block {
+            provider
+                .header_by_number(block_number)
+                .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+                .ok_or_else(|| {
+                    ErrorObject::owned::<()>(
+                        INTERNAL_ERROR_CODE,
+                        format!(
+                            "provider no longer has block {}; closing subscription",
+                            block_number
+                        ),
+                        None,
+                    )
+                })?;
+        }
+
+        Ok(Self {
+            scope: BlockScope::Range { from_block, to_block },
+            address: params.address,
+            topics: params.topics,
+        })
+    }
+}
+
+pub(crate) async fn exec_query(
+    query_params: ValidatedQueryParams,
+    pool: &SqlitePool,
+) -> Result<Vec<ShadowLogRow>, ErrorObject<'static>> {
+    let mut builder = sqlx::QueryBuilder::new(
+        "SELECT address, topic0, topic1, topic2, topic3, data, block_hash, block_number, \
+         transaction_hash, transaction_index, log_index FROM shadow_logs WHERE 1 = 1",
+    );
+
+    match &query_params.scope {
+        BlockScope::Hash(hash) => {
+            builder.push(" AND block_hash = ");
+            builder.push_bind(hash.clone());
+        }
+        BlockScope::Range { from_block, to_block } => {
+            builder.push(" AND block_number >= ");
+            builder.push_bind(*from_block as i64);
+            builder.push(" AND block_number <= ");
+            builder.push_bind(*to_block as i64);
+        }
+    }
+
+    if let Some(address) = &query_params.address {
+        match address {
+            AddressRepresentation::Single(addr) => {
+                builder.push(" AND address = ");
+                builder.push_bind(addr.clone());
+            }
+            AddressRepresentation::Multiple(addrs) => {
+                builder.push(" AND address IN (");
+                let mut separated = builder.separated(", ");
+                for addr in addrs {
+                    separated.push_bind(addr.clone());
+                }
+                builder.push(")");
+            }
+        }
+    }
+
+    // Positional topic filter, matching `eth_getLogs`/`eth_subscribe` semantics: `topics[i] ==
+    // None` is a wildcard (no constraint at that position), and `topics[i] == Some(alternatives)`
+    // requires the log's topic at that position to be one of `alternatives` (an OR-set). An empty
+    // `alternatives` list is treated as a wildcard too.
+    if let Some(topics) = &query_params.topics {
+        for (position, filter) in topics.iter().enumerate().take(4) {
+            let Some(alternatives) = filter else {
+                continue;
+            };
+            if alternatives.is_empty() {
+                continue;
+            }
+
+            let column = format!("topic{}", position);
+            builder.push(format!(" AND {} IN (", column));
+            let mut separated = builder.separated(", ");
+            for alternative in alternatives {
+                separated.push_bind(alternative.clone());
+            }
+            builder.push(")");
+        }
+    }
+
+    builder.push(" ORDER BY block_number ASC, log_index ASC");
+
+    let rows = builder
+        .build()
+        .fetch_all(pool)
+        .await
+        .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(ShadowLogRow {
+                address: row.try_get("address").map_err(sqlx_err)?,
+                topics: [0usize, 1, 2, 3]
+                    .into_iter()
+                    .filter_map(|i| row.try_get::<Option<String>, _>(format!("topic{}", i).as_str()).ok().flatten())
+                    .collect(),
+                data: row.try_get("data").map_err(sqlx_err)?,
+                block_hash: row.try_get("block_hash").map_err(sqlx_err)?,
+                block_number: row.try_get("block_number").map_err(sqlx_err)?,
+                transaction_hash: row.try_get("transaction_hash").map_err(sqlx_err)?,
+                transaction_index: row.try_get("transaction_index").map_err(sqlx_err)?,
+                log_index: row.try_get("log_index").map_err(sqlx_err)?,
+            })
+        })
+        .collect()
+}
+
+fn sqlx_err(e: sqlx::Error) -> ErrorObject<'static> {
+    ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_pool() -> SqlitePool {
+        let pool = SqlitePool::connect("sqlite::memory:").await.expect("open in-memory sqlite");
+        sqlx::query(
+            "CREATE TABLE shadow_logs (
+                address TEXT NOT NULL,
+                topic0 TEXT,
+                topic1 TEXT,
+                topic2 TEXT,
+                topic3 TEXT,
+                data TEXT NOT NULL,
+                block_hash TEXT NOT NULL,
+                block_number INTEGER NOT NULL,
+                transaction_hash TEXT NOT NULL,
+                transaction_index INTEGER NOT NULL,
+                log_index INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await
+        .expect("create shadow_logs table");
+        pool
+    }
+
+    async fn insert_log(pool: &SqlitePool, topics: [Option<&str>; 4], log_index: i64) {
+        sqlx::query(
+            "INSERT INTO shadow_logs (address, topic0, topic1, topic2, topic3, data, block_hash, \
+             block_number, transaction_hash, transaction_index, log_index) \
+             VALUES ('0xaddr', ?, ?, ?, ?, '0xdata', '0xblock', 1, '0xtx', 0, ?)",
+        )
+        .bind(topics[0])
+        .bind(topics[1])
+        .bind(topics[2])
+        .bind(topics[3])
+        .bind(log_index)
+        .execute(pool)
+        .await
+        .expect("insert shadow log row");
+    }
+
+    /// Builds `ValidatedQueryParams` directly (bypassing the provider-validating constructors,
+    /// which aren't relevant here) scoped to the single block these tests insert rows under.
+    fn hash_scoped_params(topics: Option<Vec<Option<Vec<String>>>>) -> ValidatedQueryParams {
+        ValidatedQueryParams {
+            scope: BlockScope::Hash("0xblock".to_string()),
+            address: None,
+            topics,
+        }
+    }
+
+    #[tokio::test]
+    async fn exec_query_topic_wildcard_matches_any_value_at_that_position() {
+        let pool = memory_pool().await;
+        insert_log(&pool, [Some("0x1"), Some("0x2"), None, None], 0).await;
+        insert_log(&pool, [Some("0x1"), Some("0x3"), None, None], 1).await;
+
+        // `None` at position 1 is a wildcard: both rows match regardless of their topic1.
+        let params = hash_scoped_params(Some(vec![Some(vec!["0x1".to_string()]), None]));
+        let rows = exec_query(params, &pool).await.expect("query succeeds");
+
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn exec_query_topic_or_set_matches_any_listed_alternative() {
+        let pool = memory_pool().await;
+        insert_log(&pool, [Some("0x1"), None, None, None], 0).await;
+        insert_log(&pool, [Some("0x2"), None, None, None], 1).await;
+        insert_log(&pool, [Some("0x3"), None, None, None], 2).await;
+
+        let params = hash_scoped_params(Some(vec![Some(vec![
+            "0x1".to_string(),
+            "0x2".to_string(),
+        ])]));
+        let rows = exec_query(params, &pool).await.expect("query succeeds");
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|r| r.topics[0] == "0x1" || r.topics[0] == "0x2"));
+    }
+
+    #[tokio::test]
+    async fn exec_query_treats_an_empty_alternatives_list_as_a_wildcard() {
+        let pool = memory_pool().await;
+        insert_log(&pool, [Some("0x1"), None, None, None], 0).await;
+
+        let params = hash_scoped_params(Some(vec![Some(vec![])]));
+        let rows = exec_query(params, &pool).await.expect("query succeeds");
+
+        assert_eq!(rows.len(), 1);
+    }
+}